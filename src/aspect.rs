@@ -0,0 +1,44 @@
+//! Aspects filter entities by which components they have, so a `System` only ever sees the
+//! entities it's interested in.
+
+use world::ComponentManager;
+use Entity;
+use EntityData;
+
+/// A type-erased filter over an entity's components.
+///
+/// Usually built with the `aspect!` macro rather than constructed directly. `Aspect` itself
+/// doesn't carry the `ComponentManager` type it was built against as a generic parameter (so a
+/// single concrete type can hold aspects for several different component sets), which is why
+/// building one is `unsafe`: `check` trusts that it's only ever called with the same
+/// `ComponentManager` type it was created with.
+pub struct Aspect
+{
+    check: Box<Fn(&Entity, *const ()) -> bool>,
+}
+
+impl Aspect
+{
+    /// Builds an `Aspect` from a closure that inspects an entity's components.
+    pub unsafe fn new<T, F>(check: F) -> Aspect
+        where T: ComponentManager, F: Fn(&EntityData<T>, &T) -> bool + 'static
+    {
+        Aspect
+        {
+            check: box move |entity: &Entity, components: *const ()| {
+                let components: &T = &*(components as *const T);
+                check(&EntityData(entity), components)
+            },
+        }
+    }
+
+    /// Returns `true` if `entity` currently satisfies this aspect, according to `components`.
+    ///
+    /// Callers must pass the same `ComponentManager` type this `Aspect` was built with; there's
+    /// nothing at the type level stopping a mismatched call, since the check was erased when
+    /// the `Aspect` was constructed.
+    pub fn check<T: ComponentManager>(&self, entity: &Entity, components: &T) -> bool
+    {
+        (self.check)(entity, components as *const T as *const ())
+    }
+}
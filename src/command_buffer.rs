@@ -0,0 +1,101 @@
+//! Deferred structural changes recorded during `System::process` and applied once every system
+//! has finished its pass for the frame.
+
+use component::{EntityBuilder, EntityModifier};
+use world::ComponentManager;
+use Entity;
+
+/// A handle to an entity queued for creation in a `CommandBuffer` that hasn't been created yet.
+///
+/// Later commands in the same buffer can target it (e.g. to attach more components) even
+/// though the real `Entity` id won't exist until the buffer is flushed.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub struct PlaceholderEntity(usize);
+
+pub enum Target
+{
+    Existing(Entity),
+    Placeholder(PlaceholderEntity),
+}
+
+pub enum Command<T: ComponentManager>
+{
+    CreateEntity(PlaceholderEntity, Box<EntityBuilder<T>>),
+    RemoveEntity(Target),
+    ModifyEntity(Target, Box<EntityModifier<T>>),
+}
+
+/// Queues entity creation, removal and modification so a `System` can request structural
+/// changes while it's in the middle of iterating entities, without aliasing the `World` it's
+/// reading. `World` applies the whole queue, in recording order, once every system has had its
+/// turn for the frame, then clears the buffer — so a command recorded by one system is not
+/// visible to a later system in the same frame, only from the next frame on.
+pub struct CommandBuffer<T: ComponentManager>
+{
+    commands: Vec<Command<T>>,
+    next_placeholder: usize,
+}
+
+impl<T: ComponentManager> CommandBuffer<T>
+{
+    pub fn new() -> CommandBuffer<T>
+    {
+        CommandBuffer { commands: Vec::new(), next_placeholder: 0 }
+    }
+
+    /// Queues creation of a new entity built with `builder`. Returns a placeholder that later
+    /// commands in this buffer can use to target the entity before it actually exists.
+    pub fn create_entity<B>(&mut self, builder: B) -> PlaceholderEntity where B: EntityBuilder<T> + 'static
+    {
+        let placeholder = PlaceholderEntity(self.next_placeholder);
+        self.next_placeholder += 1;
+        self.commands.push(Command::CreateEntity(placeholder, box builder));
+        placeholder
+    }
+
+    /// Queues removal of an already-existing entity.
+    pub fn remove_entity(&mut self, entity: Entity)
+    {
+        self.commands.push(Command::RemoveEntity(Target::Existing(entity)));
+    }
+
+    /// Queues removal of an entity created earlier in this same buffer.
+    pub fn remove_placeholder(&mut self, placeholder: PlaceholderEntity)
+    {
+        self.commands.push(Command::RemoveEntity(Target::Placeholder(placeholder)));
+    }
+
+    /// Queues a component change on an already-existing entity.
+    pub fn modify_entity<M>(&mut self, entity: Entity, modifier: M) where M: EntityModifier<T> + 'static
+    {
+        self.commands.push(Command::ModifyEntity(Target::Existing(entity), box modifier));
+    }
+
+    /// Queues a component change on an entity created earlier in this same buffer.
+    pub fn modify_placeholder<M>(&mut self, placeholder: PlaceholderEntity, modifier: M)
+        where M: EntityModifier<T> + 'static
+    {
+        self.commands.push(Command::ModifyEntity(Target::Placeholder(placeholder), box modifier));
+    }
+
+    /// Hands over every queued command, in recording order, clearing the buffer.
+    pub fn drain(&mut self) -> Vec<Command<T>>
+    {
+        ::std::mem::replace(&mut self.commands, Vec::new())
+    }
+}
+
+impl Target
+{
+    /// Resolves this target against entities created earlier in the same flush. `World` is the
+    /// only caller (from `flush_commands`, applying commands one at a time against its own
+    /// `DataHelper`, so it can't be done here without World taking on that dependency).
+    pub fn resolve(self, placeholders: &::std::collections::HashMap<PlaceholderEntity, Entity>) -> Option<Entity>
+    {
+        match self
+        {
+            Target::Existing(entity) => Some(entity),
+            Target::Placeholder(placeholder) => placeholders.get(&placeholder).cloned(),
+        }
+    }
+}
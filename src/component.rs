@@ -0,0 +1,535 @@
+//! Components are plain data attached to entities, and `ComponentList`s are where that data
+//! actually lives.
+
+use std::any::Any;
+use std::collections::HashMap;
+
+use Entity;
+
+/// Marker trait for anything that can be stored in a `ComponentList`.
+///
+/// There's nothing to implement; every `'static` type gets it for free.
+pub trait Component: Any {}
+
+impl<T> Component for T where T: Any {}
+
+/// Sentinel written into `SparseStorage::sparse` for entity ids with no component.
+const SPARSE_EMPTY: u32 = ::std::u32::MAX;
+
+/// A sparse set: `dense` is packed with only the present `(Entity, T)` pairs, and `sparse`
+/// maps an entity id to its slot in `dense` (or `SPARSE_EMPTY` if absent). Gives O(1)
+/// insert/remove/lookup and cache-friendly iteration over present components, at the cost of
+/// the `sparse` array growing to the largest entity id ever seen.
+struct SparseStorage<T: Component>
+{
+    dense: Vec<(Entity, T)>,
+    sparse: Vec<u32>,
+}
+
+impl<T: Component> SparseStorage<T>
+{
+    fn new() -> SparseStorage<T>
+    {
+        SparseStorage { dense: Vec::new(), sparse: Vec::new() }
+    }
+
+    fn slot(&self, entity: &Entity) -> Option<usize>
+    {
+        let id = **entity;
+        match self.sparse.get(id)
+        {
+            Some(&SPARSE_EMPTY) | None => None,
+            Some(&slot) => Some(slot as usize),
+        }
+    }
+
+    fn insert(&mut self, entity: &Entity, component: T) -> Option<T>
+    {
+        if let Some(slot) = self.slot(entity)
+        {
+            return Some(::std::mem::replace(&mut self.dense[slot].1, component));
+        }
+
+        let id = **entity;
+        if id >= self.sparse.len()
+        {
+            self.sparse.resize(id + 1, SPARSE_EMPTY);
+        }
+        self.sparse[id] = self.dense.len() as u32;
+        self.dense.push((*entity, component));
+        None
+    }
+
+    fn remove(&mut self, entity: &Entity) -> Option<T>
+    {
+        let slot = match self.slot(entity) { Some(slot) => slot, None => return None };
+
+        self.sparse[**entity] = SPARSE_EMPTY;
+        let (_, component) = self.dense.swap_remove(slot);
+
+        if slot < self.dense.len()
+        {
+            let moved_entity = self.dense[slot].0;
+            self.sparse[*moved_entity] = slot as u32;
+        }
+
+        Some(component)
+    }
+
+    fn get(&self, entity: &Entity) -> Option<&T>
+    {
+        self.slot(entity).map(|slot| &self.dense[slot].1)
+    }
+
+    fn get_mut(&mut self, entity: &Entity) -> Option<&mut T>
+    {
+        self.slot(entity).map(move |slot| &mut self.dense[slot].1)
+    }
+}
+
+enum Storage<T: Component>
+{
+    /// Backed by a dense `Vec`, indexed directly by entity id. Fast, but wastes space when
+    /// only a small fraction of entities carry the component.
+    Hot(Vec<Option<T>>),
+    /// Backed by a `HashMap`. Slower per-access, but doesn't pay for entities that never get
+    /// the component at all.
+    Cold(HashMap<usize, T>),
+    /// Backed by a dense/sparse pair (see `SparseStorage`). For components that sit on a
+    /// large, fragmented subset of entities, where `Hot`'s `Vec<Option<T>>` wastes memory on
+    /// every absent entity but `Cold`'s hashing is more than this needs.
+    Sparse(SparseStorage<T>),
+}
+
+/// Storage for every instance of a single component type, one per entity at most.
+///
+/// `ComponentList` is only ever mutated through the `unsafe` methods below, which is how
+/// `BuildData`, `ModifyData` and `EntityData` get away with looking like safe, borrow-checked
+/// accessors: the library, not the compiler, is responsible for making sure two mutable
+/// borrows of the same list never coexist.
+pub struct ComponentList<T: Component>
+{
+    storage: Storage<T>,
+    on_add: Option<Box<Fn(&Entity, &T)>>,
+    on_insert: Option<Box<Fn(&Entity, &T)>>,
+    on_remove: Option<Box<Fn(&Entity)>>,
+}
+
+impl<T: Component> ComponentList<T>
+{
+    /// A list backed by a dense `Vec`, indexed directly by entity id.
+    pub fn hot() -> ComponentList<T>
+    {
+        ComponentList
+        {
+            storage: Storage::Hot(Vec::new()),
+            on_add: None,
+            on_insert: None,
+            on_remove: None,
+        }
+    }
+
+    /// A list backed by a `HashMap`, for components only a few entities carry.
+    pub fn cold() -> ComponentList<T>
+    {
+        ComponentList
+        {
+            storage: Storage::Cold(HashMap::new()),
+            on_add: None,
+            on_insert: None,
+            on_remove: None,
+        }
+    }
+
+    /// A list backed by a sparse set: attached to a large, fragmented subset of entities,
+    /// where `hot`'s dense `Vec` would waste memory but `cold`'s hashing is overkill.
+    pub fn sparse() -> ComponentList<T>
+    {
+        ComponentList
+        {
+            storage: Storage::Sparse(SparseStorage::new()),
+            on_add: None,
+            on_insert: None,
+            on_remove: None,
+        }
+    }
+
+    /// Registers a callback fired the first time a component is attached to an entity (i.e.
+    /// whenever `insert` returns `None`).
+    ///
+    /// Must not itself call `insert`/`remove`/`borrow` on this same `ComponentList`; doing so
+    /// would alias the mutable borrow already in progress in the caller.
+    pub fn on_add<F>(mut self, callback: F) -> ComponentList<T> where F: Fn(&Entity, &T) + 'static
+    {
+        self.on_add = Some(box callback);
+        self
+    }
+
+    /// Registers a callback fired on every `insert`, whether it's a fresh attach or an
+    /// overwrite of an existing component.
+    ///
+    /// Same reentrancy restriction as `on_add`.
+    pub fn on_insert<F>(mut self, callback: F) -> ComponentList<T> where F: Fn(&Entity, &T) + 'static
+    {
+        self.on_insert = Some(box callback);
+        self
+    }
+
+    /// Registers a callback fired whenever a component is detached (i.e. whenever `remove`
+    /// returns `Some`).
+    ///
+    /// Same reentrancy restriction as `on_add`.
+    pub fn on_remove<F>(mut self, callback: F) -> ComponentList<T> where F: Fn(&Entity) + 'static
+    {
+        self.on_remove = Some(box callback);
+        self
+    }
+
+    /// Sets the `on_add` callback in place. Used by the `components!` macro, which builds the
+    /// list before it has a binding to consume with the `self`-taking builder methods above.
+    pub fn set_on_add<F>(&mut self, callback: F) where F: Fn(&Entity, &T) + 'static
+    {
+        self.on_add = Some(box callback);
+    }
+
+    /// Sets the `on_insert` callback in place. See `set_on_add`.
+    pub fn set_on_insert<F>(&mut self, callback: F) where F: Fn(&Entity, &T) + 'static
+    {
+        self.on_insert = Some(box callback);
+    }
+
+    /// Sets the `on_remove` callback in place. See `set_on_add`.
+    pub fn set_on_remove<F>(&mut self, callback: F) where F: Fn(&Entity) + 'static
+    {
+        self.on_remove = Some(box callback);
+    }
+
+    /// Wraps any existing `on_add` hook so both it and `callback` run, old one first, rather
+    /// than replacing it outright. Used by things like the observer registry that can't assume
+    /// they're the only thing watching a list.
+    pub fn chain_on_add<F>(&mut self, callback: F) where F: Fn(&Entity, &T) + 'static
+    {
+        let previous = self.on_add.take();
+        self.on_add = Some(box move |entity: &Entity, component: &T| {
+            if let Some(ref previous) = previous { previous(entity, component); }
+            callback(entity, component);
+        });
+    }
+
+    /// See `chain_on_add`.
+    pub fn chain_on_insert<F>(&mut self, callback: F) where F: Fn(&Entity, &T) + 'static
+    {
+        let previous = self.on_insert.take();
+        self.on_insert = Some(box move |entity: &Entity, component: &T| {
+            if let Some(ref previous) = previous { previous(entity, component); }
+            callback(entity, component);
+        });
+    }
+
+    /// See `chain_on_add`.
+    pub fn chain_on_remove<F>(&mut self, callback: F) where F: Fn(&Entity) + 'static
+    {
+        let previous = self.on_remove.take();
+        self.on_remove = Some(box move |entity: &Entity| {
+            if let Some(ref previous) = previous { previous(entity); }
+            callback(entity);
+        });
+    }
+
+    /// Attaches `component` to `entity`, returning whatever was there before.
+    ///
+    /// Fires `on_add` when nothing was there before, and `on_insert` unconditionally.
+    pub unsafe fn insert(&mut self, entity: &Entity, component: T) -> Option<T>
+    {
+        let previous = match self.storage
+        {
+            Storage::Hot(ref mut vec) =>
+            {
+                let index = **entity;
+                if index >= vec.len()
+                {
+                    vec.resize_with(index + 1, || None);
+                }
+                ::std::mem::replace(&mut vec[index], Some(component))
+            },
+            Storage::Cold(ref mut map) => map.insert(**entity, component),
+            Storage::Sparse(ref mut sparse) => sparse.insert(entity, component),
+        };
+
+        if previous.is_none()
+        {
+            if let Some(ref on_add) = self.on_add
+            {
+                on_add(entity, self.get_ref(entity).unwrap());
+            }
+        }
+        if let Some(ref on_insert) = self.on_insert
+        {
+            on_insert(entity, self.get_ref(entity).unwrap());
+        }
+
+        previous
+    }
+
+    /// Detaches whatever component `entity` has, if any, firing `on_remove` when there was one.
+    pub unsafe fn remove(&mut self, entity: &Entity) -> Option<T>
+    {
+        let removed = match self.storage
+        {
+            Storage::Hot(ref mut vec) =>
+            {
+                let index = **entity;
+                if index < vec.len() { vec[index].take() } else { None }
+            },
+            Storage::Cold(ref mut map) => map.remove(&**entity),
+            Storage::Sparse(ref mut sparse) => sparse.remove(entity),
+        };
+
+        if removed.is_some()
+        {
+            if let Some(ref on_remove) = self.on_remove
+            {
+                on_remove(entity);
+            }
+        }
+
+        removed
+    }
+
+    /// Returns a clone of `entity`'s component, if it has one.
+    pub unsafe fn get(&self, entity: &Entity) -> Option<T> where T: Clone
+    {
+        self.get_ref(entity).cloned()
+    }
+
+    /// Returns a mutable borrow of `entity`'s component, if it has one.
+    pub unsafe fn borrow(&mut self, entity: &Entity) -> Option<&mut T>
+    {
+        match self.storage
+        {
+            Storage::Hot(ref mut vec) =>
+            {
+                let index = **entity;
+                if index < vec.len() { vec[index].as_mut() } else { None }
+            },
+            Storage::Cold(ref mut map) => map.get_mut(&**entity),
+            Storage::Sparse(ref mut sparse) => sparse.get_mut(entity),
+        }
+    }
+
+    /// Returns `true` if `entity` currently has a component in this list.
+    pub unsafe fn has(&self, entity: &Entity) -> bool
+    {
+        self.get_ref(entity).is_some()
+    }
+
+    /// Borrows `entity`'s component without requiring `&mut self`. Exists for callers (like
+    /// `Query`) that only need a read-only view and would otherwise have to fight the borrow
+    /// checker to get one out of `borrow`.
+    pub unsafe fn peek(&self, entity: &Entity) -> Option<&T>
+    {
+        self.get_ref(entity)
+    }
+
+    /// Every entity currently present in this list, in unspecified order.
+    pub fn entities(&self) -> Vec<Entity>
+    {
+        match self.storage
+        {
+            Storage::Hot(ref vec) => vec.iter().enumerate()
+                .filter_map(|(i, slot)| if slot.is_some() { Some(Entity(i)) } else { None })
+                .collect(),
+            Storage::Cold(ref map) => map.keys().map(|&id| Entity(id)).collect(),
+            Storage::Sparse(ref sparse) => sparse.dense.iter().map(|&(entity, _)| entity).collect(),
+        }
+    }
+
+    /// How many entities currently carry this component. Used to pick the cheapest list to
+    /// drive iteration when joining several lists together (eg: in a `Query`).
+    pub fn len(&self) -> usize
+    {
+        match self.storage
+        {
+            Storage::Hot(ref vec) => vec.iter().filter(|slot| slot.is_some()).count(),
+            Storage::Cold(ref map) => map.len(),
+            Storage::Sparse(ref sparse) => sparse.dense.len(),
+        }
+    }
+
+    fn get_ref(&self, entity: &Entity) -> Option<&T>
+    {
+        match self.storage
+        {
+            Storage::Hot(ref vec) =>
+            {
+                let index = **entity;
+                if index < vec.len() { vec[index].as_ref() } else { None }
+            },
+            Storage::Cold(ref map) => map.get(&**entity),
+            Storage::Sparse(ref sparse) => sparse.get(entity),
+        }
+    }
+}
+
+/// A closure used by `World::create_entity` to populate a freshly-created entity's components.
+pub trait EntityBuilder<T>
+{
+    fn build(&mut self, entity: ::BuildData<T>, components: &mut T);
+}
+
+impl<T, F> EntityBuilder<T> for F where F: FnMut(::BuildData<T>, &mut T)
+{
+    fn build(&mut self, entity: ::BuildData<T>, components: &mut T)
+    {
+        self(entity, components)
+    }
+}
+
+/// A closure used by `World::modify_entity` to add/remove/change an existing entity's
+/// components.
+pub trait EntityModifier<T>
+{
+    fn modify(&mut self, entity: ::ModifyData<T>, components: &mut T);
+}
+
+impl<T, F> EntityModifier<T> for F where F: FnMut(::ModifyData<T>, &mut T)
+{
+    fn modify(&mut self, entity: ::ModifyData<T>, components: &mut T)
+    {
+        self(entity, components)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use Entity;
+
+    #[test]
+    fn sparse_swap_remove_fixes_up_the_moved_entity()
+    {
+        let mut list: ComponentList<&'static str> = ComponentList::sparse();
+
+        unsafe
+        {
+            list.insert(&Entity(0), "a");
+            list.insert(&Entity(1), "b");
+            list.insert(&Entity(2), "c");
+
+            // Removing the middle entry swap-removes the last dense slot ("c") into it; the
+            // fixup has to repoint sparse[2] at that new slot or "c" becomes unreachable.
+            assert_eq!(list.remove(&Entity(1)), Some("b"));
+
+            assert_eq!(list.get(&Entity(0)), Some("a"));
+            assert_eq!(list.get(&Entity(1)), None);
+            assert_eq!(list.get(&Entity(2)), Some("c"));
+        }
+    }
+
+    #[test]
+    fn sparse_insert_overwrites_in_place_without_touching_other_entities()
+    {
+        let mut list: ComponentList<u32> = ComponentList::sparse();
+
+        unsafe
+        {
+            list.insert(&Entity(5), 1);
+            list.insert(&Entity(9), 2);
+
+            assert_eq!(list.insert(&Entity(5), 10), Some(1));
+
+            assert_eq!(list.get(&Entity(5)), Some(10));
+            assert_eq!(list.get(&Entity(9)), Some(2));
+            assert_eq!(list.entities().len(), 2);
+        }
+    }
+
+    #[test]
+    fn on_add_fires_only_on_the_first_insert_but_on_insert_fires_every_time()
+    {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let adds = Rc::new(RefCell::new(Vec::new()));
+        let inserts = Rc::new(RefCell::new(Vec::new()));
+
+        let adds_in_callback = adds.clone();
+        let inserts_in_callback = inserts.clone();
+        let list: ComponentList<u32> = ComponentList::hot()
+            .on_add(move |entity: &Entity, value: &u32| { adds_in_callback.borrow_mut().push((**entity, *value)); })
+            .on_insert(move |entity: &Entity, value: &u32| { inserts_in_callback.borrow_mut().push((**entity, *value)); });
+        let mut list = list;
+
+        unsafe
+        {
+            list.insert(&Entity(0), 1);
+            list.insert(&Entity(0), 2);
+        }
+
+        assert_eq!(*adds.borrow(), vec![(0, 1)]);
+        assert_eq!(*inserts.borrow(), vec![(0, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn chain_on_add_runs_both_the_previous_hook_and_the_new_one()
+    {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+
+        let seen_first = seen.clone();
+        let mut list: ComponentList<u32> = ComponentList::hot()
+            .on_add(move |_: &Entity, _: &u32| { seen_first.borrow_mut().push("first"); });
+
+        let seen_second = seen.clone();
+        list.chain_on_add(move |_: &Entity, _: &u32| { seen_second.borrow_mut().push("second"); });
+
+        unsafe { list.insert(&Entity(0), 1); }
+
+        assert_eq!(*seen.borrow(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn chain_on_remove_runs_both_the_previous_hook_and_the_new_one()
+    {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+
+        let seen_first = seen.clone();
+        let mut list: ComponentList<u32> = ComponentList::hot()
+            .on_remove(move |_: &Entity| { seen_first.borrow_mut().push("first"); });
+
+        let seen_second = seen.clone();
+        list.chain_on_remove(move |_: &Entity| { seen_second.borrow_mut().push("second"); });
+
+        unsafe
+        {
+            list.insert(&Entity(0), 1);
+            list.remove(&Entity(0));
+        }
+
+        assert_eq!(*seen.borrow(), vec!["first", "second"]);
+    }
+
+    #[test]
+    fn sparse_repeated_insert_and_remove_of_the_same_id_stays_consistent()
+    {
+        let mut list: ComponentList<u32> = ComponentList::sparse();
+
+        unsafe
+        {
+            for i in 0..3
+            {
+                assert_eq!(list.insert(&Entity(4), i), None);
+                assert_eq!(list.remove(&Entity(4)), Some(i));
+                assert_eq!(list.remove(&Entity(4)), None);
+            }
+
+            assert!(list.entities().is_empty());
+        }
+    }
+}
@@ -0,0 +1,45 @@
+
+//! Entity identifiers.
+
+use std::ops::Deref;
+
+/// An identifier for an entity.
+///
+/// An `Entity` carries no data or logic of its own; it is simply a handle used to look up
+/// components in a `ComponentList` and to track activation state in a `World`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct Entity(pub usize);
+
+impl Deref for Entity
+{
+    type Target = usize;
+    fn deref(&self) -> &usize
+    {
+        &self.0
+    }
+}
+
+/// An iterator over a set of `Entity` values, hiding whatever concrete collection they came
+/// from (a `Vec`, a `TrieMap`'s values, ...) behind one type.
+pub struct EntityIter<'a>
+{
+    inner: Box<Iterator<Item=&'a Entity> + 'a>,
+}
+
+impl<'a> EntityIter<'a>
+{
+    pub fn new<I>(entities: I) -> EntityIter<'a> where I: Iterator<Item=&'a Entity> + 'a
+    {
+        EntityIter { inner: box entities }
+    }
+}
+
+impl<'a> Iterator for EntityIter<'a>
+{
+    type Item = &'a Entity;
+
+    fn next(&mut self) -> Option<&'a Entity>
+    {
+        self.inner.next()
+    }
+}
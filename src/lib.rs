@@ -32,17 +32,21 @@
 #![feature(box_syntax, core, collections, hash, std_misc)]
 
 pub use aspect::Aspect;
+pub use command_buffer::{CommandBuffer, PlaceholderEntity};
 pub use component::{Component, ComponentList};
 pub use component::{EntityBuilder, EntityModifier};
 pub use entity::{Entity, EntityIter};
+pub use query::{Query, QueryParam, Joined};
 pub use system::{System, Process};
-pub use world::{ComponentManager, SystemManager, DataHelper, World};
+pub use world::{ComponentManager, SystemManager, DataHelper, Event, TriggerSink, World};
 
 use std::ops::{Deref};
 
 pub mod aspect;
+pub mod command_buffer;
 pub mod component;
 pub mod entity;
+pub mod query;
 pub mod system;
 pub mod world;
 
@@ -117,7 +121,12 @@ mod macros
     macro_rules! components {
         {
             $Name:ident {
-                $(#[$kind:ident] $field_name:ident : $field_ty:ty),+
+                $(
+                    #[$kind:ident] $field_name:ident : $field_ty:ty
+                    $(, on_add: $on_add:expr)*
+                    $(, on_insert: $on_insert:expr)*
+                    $(, on_remove: $on_remove:expr)*
+                ),+
             }
         } => {
             pub struct $Name {
@@ -132,7 +141,13 @@ mod macros
                 {
                     $Name {
                         $(
-                            $field_name : $crate::ComponentList::$kind(),
+                            $field_name : {
+                                let mut list = $crate::ComponentList::$kind();
+                                $( list.set_on_add($on_add); )*
+                                $( list.set_on_insert($on_insert); )*
+                                $( list.set_on_remove($on_remove); )*
+                                list
+                            },
                         )+
                     }
                 }
@@ -0,0 +1,195 @@
+//! `Query` turns the usual "check the aspect, then fetch each component" boilerplate inside
+//! `System::process` into a single typed loop.
+
+use component::{Component, ComponentList};
+use Entity;
+
+/// One borrowed `ComponentList` participating in a `Query`. Implemented for `&ComponentList<T>`
+/// (yielding `&T`) and `&mut ComponentList<T>` (yielding `&mut T`); which one you pass decides
+/// whether the query reads or writes that component.
+pub trait QueryParam<'a>
+{
+    type Item;
+
+    fn has(&self, entity: &Entity) -> bool;
+    fn len(&self) -> usize;
+    fn entities(&self) -> Vec<Entity>;
+    unsafe fn fetch(&mut self, entity: &Entity) -> Self::Item;
+}
+
+impl<'a, T: Component> QueryParam<'a> for &'a ComponentList<T>
+{
+    type Item = &'a T;
+
+    fn has(&self, entity: &Entity) -> bool { unsafe { ComponentList::has(self, entity) } }
+    fn len(&self) -> usize { ComponentList::len(self) }
+    fn entities(&self) -> Vec<Entity> { ComponentList::entities(self) }
+
+    unsafe fn fetch(&mut self, entity: &Entity) -> &'a T
+    {
+        // The list outlives 'a (it's *borrowed* for 'a), so extending this borrow to 'a is
+        // sound as long as callers (ie: `Query`) never hand out two overlapping borrows of the
+        // same entity's component.
+        ::std::mem::transmute(self.peek(entity).unwrap())
+    }
+}
+
+impl<'a, T: Component> QueryParam<'a> for &'a mut ComponentList<T>
+{
+    type Item = &'a mut T;
+
+    fn has(&self, entity: &Entity) -> bool { unsafe { ComponentList::has(self, entity) } }
+    fn len(&self) -> usize { ComponentList::len(self) }
+    fn entities(&self) -> Vec<Entity> { ComponentList::entities(self) }
+
+    unsafe fn fetch(&mut self, entity: &Entity) -> &'a mut T
+    {
+        ::std::mem::transmute(self.borrow(entity).unwrap())
+    }
+}
+
+/// A tuple of `QueryParam`s joined together: present only for entities that satisfy every
+/// member.
+pub trait Joined<'a>
+{
+    type Item;
+
+    fn has_all(&self, entity: &Entity) -> bool;
+    fn smallest_entities(&self) -> Vec<Entity>;
+    unsafe fn fetch_all(&mut self, entity: &Entity) -> Self::Item;
+}
+
+macro_rules! impl_joined {
+    ($($idx:tt => $name:ident),+) => {
+        impl<'a, $($name: QueryParam<'a>),+> Joined<'a> for ($($name,)+)
+        {
+            type Item = ($($name::Item,)+);
+
+            fn has_all(&self, entity: &Entity) -> bool
+            {
+                $(self.$idx.has(entity))&&+
+            }
+
+            fn smallest_entities(&self) -> Vec<Entity>
+            {
+                let lens = [$(self.$idx.len()),+];
+                let mut smallest = 0;
+                for i in 1..lens.len()
+                {
+                    if lens[i] < lens[smallest] { smallest = i; }
+                }
+                match smallest
+                {
+                    $($idx => self.$idx.entities(),)+
+                    _ => unreachable!(),
+                }
+            }
+
+            unsafe fn fetch_all(&mut self, entity: &Entity) -> Self::Item
+            {
+                ($(self.$idx.fetch(entity),)+)
+            }
+        }
+    };
+}
+
+impl_joined!(0 => A, 1 => B);
+impl_joined!(0 => A, 1 => B, 2 => C);
+impl_joined!(0 => A, 1 => B, 2 => C, 3 => D);
+
+/// Iterates every entity matching every member of a tuple of borrowed `ComponentList`s,
+/// yielding their components together instead of forcing the caller to re-fetch each one by
+/// hand.
+///
+/// ```ignore
+/// for (pos, vel) in Query::new((&mut components.position, &components.velocity))
+/// {
+///     pos.x += vel.x;
+/// }
+/// ```
+pub struct Query<'a, J: Joined<'a>>
+{
+    joined: J,
+    entities: Vec<Entity>,
+    cursor: usize,
+}
+
+impl<'a, J: Joined<'a>> Query<'a, J>
+{
+    pub fn new(joined: J) -> Query<'a, J>
+    {
+        let entities = joined.smallest_entities();
+        Query { joined: joined, entities: entities, cursor: 0 }
+    }
+}
+
+impl<'a, J: Joined<'a>> Iterator for Query<'a, J>
+{
+    type Item = J::Item;
+
+    fn next(&mut self) -> Option<J::Item>
+    {
+        while self.cursor < self.entities.len()
+        {
+            let entity = self.entities[self.cursor];
+            self.cursor += 1;
+            if self.joined.has_all(&entity)
+            {
+                return Some(unsafe { self.joined.fetch_all(&entity) });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use component::ComponentList;
+    use Entity;
+
+    #[test]
+    fn query_skips_entities_missing_one_member()
+    {
+        let mut pos: ComponentList<u32> = ComponentList::hot();
+        let mut vel: ComponentList<u32> = ComponentList::hot();
+
+        unsafe
+        {
+            pos.insert(&Entity(0), 10);
+            vel.insert(&Entity(0), 1);
+
+            // Entity 1 has a position but no velocity, so it should be skipped entirely.
+            pos.insert(&Entity(1), 20);
+
+            pos.insert(&Entity(2), 30);
+            vel.insert(&Entity(2), 3);
+        }
+
+        let results: Vec<(u32, u32)> = Query::new((&pos, &vel)).map(|(p, v)| (*p, *v)).collect();
+
+        assert_eq!(results, vec![(10, 1), (30, 3)]);
+    }
+
+    #[test]
+    fn query_written_through_a_mut_member_is_visible_afterwards()
+    {
+        let mut pos: ComponentList<u32> = ComponentList::hot();
+        let vel: ComponentList<u32> = ComponentList::hot();
+        let mut vel = vel;
+
+        unsafe
+        {
+            pos.insert(&Entity(0), 10);
+            vel.insert(&Entity(0), 1);
+        }
+
+        for (p, v) in Query::new((&mut pos, &vel))
+        {
+            *p += *v;
+        }
+
+        assert_eq!(unsafe { pos.get(&Entity(0)) }, Some(11));
+    }
+}
@@ -1,114 +1,173 @@
 
-//! System to specifically deal with interactions between two types of entity.
+//! System to deal with interactions between N groups of entities (eg: projectiles, targets and
+//! shields all reacting to each other at once).
 
 use std::collections::TrieMap;
 
 use Aspect;
-use EntityData;
 use Entity;
-use {Active, Passive, System};
-use World;
+use EntityIter;
+use system::{Process, System};
+use world::DataHelper;
 
 pub trait InteractProcess: System
 {
-    fn process<'a, T: Iterator<&'a Entity>>(&self, T, T, &mut EntityData);
+    /// `groups` has one entry per `Aspect` the owning `InteractSystem` was built with, in the
+    /// same order, each iterating the entities currently matching that aspect.
+    fn process<'a>(&self, groups: &mut [EntityIter<'a>], data: &mut DataHelper<Self::Components>);
 }
 
 pub struct InteractSystem<T: InteractProcess>
 {
-    interested_a: TrieMap<Entity>,
-    interested_b: TrieMap<Entity>,
-    aspect_a: Aspect,
-    aspect_b: Aspect,
+    interested: Vec<TrieMap<Entity>>,
+    aspects: Vec<Aspect>,
     inner: T,
 }
 
 impl<T: InteractProcess> InteractSystem<T>
 {
-    pub fn new(inner: T, aspect_a: Aspect, aspect_b: Aspect) -> InteractSystem<T>
+    /// Builds an `InteractSystem` tracking one entity group per element of `aspects`.
+    pub fn new(inner: T, aspects: Vec<Aspect>) -> InteractSystem<T>
     {
-        InteractSystem
-        {
-            interested_a: TrieMap::new(),
-            interested_b: TrieMap::new(),
-            aspect_a: aspect_a,
-            aspect_b: aspect_b,
-            inner: inner,
-        }
+        let interested = aspects.iter().map(|_| TrieMap::new()).collect();
+        InteractSystem { interested: interested, aspects: aspects, inner: inner }
+    }
+
+    /// Convenience constructor for the common two-group case, so existing callers don't have
+    /// to build a `Vec` themselves.
+    pub fn new_pair(inner: T, aspect_a: Aspect, aspect_b: Aspect) -> InteractSystem<T>
+    {
+        InteractSystem::new(inner, vec![aspect_a, aspect_b])
     }
 }
 
-impl<T: InteractProcess> Active for InteractSystem<T>
+impl<T: InteractProcess> Process for InteractSystem<T>
 {
-    fn process(&mut self, c: &mut EntityData)
+    fn process(&mut self, data: &mut DataHelper<T::Components>)
     {
-        self.inner.process(self.interested_a.values(), self.interested_b.values(), c);
+        let mut groups: Vec<EntityIter> =
+            self.interested.iter().map(|group| EntityIter::new(group.values())).collect();
+        self.inner.process(&mut groups, data);
     }
 }
 
 impl<T: InteractProcess> System for InteractSystem<T>
 {
-    fn activated(&mut self, entity: &Entity, world: &World)
+    type Components = T::Components;
+
+    fn activated(&mut self, entity: &Entity, components: &T::Components)
     {
-        if self.aspect_a.check(entity, world)
+        for (aspect, interested) in self.aspects.iter().zip(self.interested.iter_mut())
         {
-            self.interested_a.insert(**entity, entity.clone());
-            self.inner.activated(entity, world);
-        }
-        if self.aspect_b.check(entity, world)
-        {
-            self.interested_b.insert(**entity, entity.clone());
-            self.inner.activated(entity, world);
+            if aspect.check(entity, components)
+            {
+                interested.insert(**entity, entity.clone());
+                self.inner.activated(entity, components);
+            }
         }
     }
 
-    fn reactivated(&mut self, entity: &Entity, world: &World)
+    fn reactivated(&mut self, entity: &Entity, components: &T::Components)
     {
-        if self.interested_a.contains_key(&**entity)
+        for (aspect, interested) in self.aspects.iter().zip(self.interested.iter_mut())
         {
-            if self.aspect_a.check(entity, world)
-            {
-                self.inner.reactivated(entity, world);
-            }
-            else
+            let was_interested = interested.contains_key(&**entity);
+            let matches = aspect.check(entity, components);
+
+            if was_interested && matches
             {
-                self.interested_a.remove(&**entity);
-                self.inner.deactivated(entity, world);
+                self.inner.reactivated(entity, components);
             }
-        }
-        else if self.aspect_a.check(entity, world)
-        {
-            self.interested_a.insert(**entity, entity.clone());
-            self.inner.activated(entity, world);
-        }
-        if self.interested_b.contains_key(&**entity)
-        {
-            if self.aspect_b.check(entity, world)
+            else if was_interested
             {
-                self.inner.reactivated(entity, world);
+                interested.remove(&**entity);
+                self.inner.deactivated(entity, components);
             }
-            else
+            else if matches
             {
-                self.interested_b.remove(&**entity);
-                self.inner.deactivated(entity, world);
+                interested.insert(**entity, entity.clone());
+                self.inner.activated(entity, components);
             }
         }
-        else if self.aspect_b.check(entity, world)
+    }
+
+    fn deactivated(&mut self, entity: &Entity, components: &T::Components)
+    {
+        for interested in self.interested.iter_mut()
         {
-            self.interested_b.insert(**entity, entity.clone());
-            self.inner.activated(entity, world);
+            if interested.remove(&**entity).is_some()
+            {
+                self.inner.deactivated(entity, components);
+            }
         }
     }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use component::ComponentList;
+    use world::{ComponentManager, DataHelper};
+    use {Aspect, EntityData};
+
+    struct Components
+    {
+        a: ComponentList<u32>,
+        b: ComponentList<u32>,
+    }
+
+    unsafe impl ComponentManager for Components
+    {
+        unsafe fn new() -> Components { Components { a: ComponentList::hot(), b: ComponentList::hot() } }
+        unsafe fn remove_all(&mut self, entity: &Entity) { self.a.remove(entity); self.b.remove(entity); }
+    }
+
+    struct CountingInner
+    {
+        activated: Rc<RefCell<Vec<usize>>>,
+    }
 
-    fn deactivated(&mut self, entity: &Entity, world: &World)
+    impl System for CountingInner
     {
-        if self.interested_a.remove(&**entity).is_some()
+        type Components = Components;
+
+        fn activated(&mut self, entity: &Entity, _: &Components)
         {
-            self.inner.deactivated(entity, world);
+            self.activated.borrow_mut().push(**entity);
         }
-        if self.interested_b.remove(&**entity).is_some()
+    }
+
+    impl InteractProcess for CountingInner
+    {
+        fn process<'a>(&self, _groups: &mut [EntityIter<'a>], _data: &mut DataHelper<Components>) {}
+    }
+
+    #[test]
+    fn activated_fires_once_per_group_an_entity_newly_matches()
+    {
+        let activated = Rc::new(RefCell::new(Vec::new()));
+        let inner = CountingInner { activated: activated.clone() };
+
+        let aspect_a = unsafe { Aspect::new(|en: &EntityData<Components>, co: &Components| en.has(&co.a)) };
+        let aspect_b = unsafe { Aspect::new(|en: &EntityData<Components>, co: &Components| en.has(&co.b)) };
+        let mut system = InteractSystem::new_pair(inner, aspect_a, aspect_b);
+
+        let mut components = unsafe { Components::new() };
+        let entity = Entity(0);
+        unsafe
         {
-            self.inner.deactivated(entity, world);
+            components.a.insert(&entity, 1);
+            components.b.insert(&entity, 2);
         }
+
+        // The entity matches both aspects at once, so `activated` on the inner system should
+        // fire twice -- once per group it joined -- not once overall.
+        system.activated(&entity, &components);
+
+        assert_eq!(*activated.borrow(), vec![0, 0]);
     }
 }
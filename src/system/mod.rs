@@ -0,0 +1,35 @@
+//! Systems are where the logic that acts on components lives.
+
+use world::{ComponentManager, DataHelper};
+use Entity;
+
+pub mod interactsystem;
+
+/// Common lifecycle every system gets notified of, regardless of how it processes entities.
+pub trait System: 'static
+{
+    type Components: ComponentManager;
+
+    /// Called when `entity` starts matching whatever this system cares about.
+    #[allow(unused_variables)]
+    fn activated(&mut self, entity: &Entity, components: &Self::Components) {}
+
+    /// Called when `entity` still matches, but its components changed.
+    #[allow(unused_variables)]
+    fn reactivated(&mut self, entity: &Entity, components: &Self::Components) {}
+
+    /// Called when `entity` stops matching.
+    #[allow(unused_variables)]
+    fn deactivated(&mut self, entity: &Entity, components: &Self::Components) {}
+}
+
+/// A system driven directly by the `SystemManager::update` loop each frame.
+///
+/// Implementors decide `is_active` themselves; disabled systems are skipped entirely rather
+/// than called with nothing to do.
+pub trait Process: System
+{
+    fn is_active(&self) -> bool { true }
+
+    fn process(&mut self, data: &mut DataHelper<Self::Components>);
+}
@@ -0,0 +1,447 @@
+//! The `World` owns every entity, component and system, and drives them forward one frame at
+//! a time.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use {BuildData, ModifyData};
+use command_buffer::{Command, CommandBuffer, PlaceholderEntity};
+use component::{Component, ComponentList, EntityBuilder, EntityModifier};
+use Entity;
+use EntityData;
+
+/// Implemented by the struct generated from the `components!` macro; bundles every
+/// `ComponentList` a `World` needs to track together.
+pub unsafe trait ComponentManager: 'static
+{
+    unsafe fn new() -> Self;
+    unsafe fn remove_all(&mut self, entity: &Entity);
+}
+
+/// Implemented by the struct generated from the `systems!` macro; bundles every `System` a
+/// `World` runs each frame together.
+pub unsafe trait SystemManager<T: ComponentManager>: 'static
+{
+    unsafe fn new() -> Self;
+    unsafe fn activated(&mut self, en: EntityData<T>, co: &T);
+    unsafe fn reactivated(&mut self, en: EntityData<T>, co: &T);
+    unsafe fn deactivated(&mut self, en: EntityData<T>, co: &T);
+    unsafe fn update(&mut self, co: &mut DataHelper<T>);
+}
+
+/// Bundles a `ComponentManager` with the bookkeeping `EntityData` needs to borrow components
+/// safely. Systems receive this (rather than the raw components) while processing.
+pub struct DataHelper<T: ComponentManager>
+{
+    pub components: T,
+    /// Queues structural changes recorded while a system is mid-`process`, so it doesn't have
+    /// to alias the `World` it's reading to create or remove entities. Flushed by `World` once
+    /// every system has run for the frame, not after each system, so a later system in the same
+    /// frame doesn't see them.
+    pub commands: CommandBuffer<T>,
+    entities: EntityManager,
+}
+
+impl<T: ComponentManager> DataHelper<T>
+{
+    fn new() -> DataHelper<T>
+    {
+        DataHelper
+        {
+            components: unsafe { ComponentManager::new() },
+            commands: CommandBuffer::new(),
+            entities: EntityManager::new(),
+        }
+    }
+
+    /// Runs `f` with an `EntityData` for `entity`, provided it's still live.
+    pub fn with_entity_data<F, R>(&mut self, entity: &Entity, mut f: F) -> Option<R>
+        where F: FnMut(EntityData<T>, &mut T) -> R
+    {
+        if self.entities.is_valid(entity)
+        {
+            Some(f(EntityData(entity), &mut self.components))
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+struct EntityManager
+{
+    activated: Vec<bool>,
+    free: Vec<usize>,
+    next_id: usize,
+}
+
+impl EntityManager
+{
+    fn new() -> EntityManager
+    {
+        EntityManager { activated: Vec::new(), free: Vec::new(), next_id: 0 }
+    }
+
+    fn create(&mut self) -> Entity
+    {
+        let id = match self.free.pop()
+        {
+            Some(id) => id,
+            None =>
+            {
+                let id = self.next_id;
+                self.next_id += 1;
+                self.activated.push(false);
+                id
+            },
+        };
+        self.activated[id] = true;
+        Entity(id)
+    }
+
+    fn destroy(&mut self, entity: &Entity)
+    {
+        let id = **entity;
+        if id < self.activated.len()
+        {
+            self.activated[id] = false;
+            self.free.push(id);
+        }
+    }
+
+    fn is_valid(&self, entity: &Entity) -> bool
+    {
+        let id = **entity;
+        id < self.activated.len() && self.activated[id]
+    }
+}
+
+/// The kind of component-list transition an observer can react to.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Show)]
+pub enum Event
+{
+    /// A component was attached where there wasn't one before.
+    OnAdd,
+    /// A component was inserted, whether freshly attached or overwriting an old value.
+    OnInsert,
+    /// A component was detached.
+    OnRemove,
+}
+
+/// How many times the trigger queue will be fully drained before `World::update` gives up,
+/// discards whatever's left and reports an overflow, to guard against observers that
+/// perpetually re-trigger each other.
+const DEFAULT_MAX_TRIGGER_ITERATIONS: usize = 64;
+
+type ObserverKey = (Event, usize);
+
+/// A cloneable handle onto a `World`'s trigger queue, obtained via `World::trigger_sink`. Wires
+/// a `ComponentList`'s lifecycle hooks so its changes get recorded for `World::register_observer`
+/// to dispatch later, without ever needing to borrow the whole `World` and one of its own
+/// `ComponentList`s mutably at the same time.
+pub struct TriggerSink
+{
+    queue: Rc<RefCell<VecDeque<(Event, usize, Entity)>>>,
+}
+
+impl TriggerSink
+{
+    /// Chains onto `list`'s `event` hook so that every future occurrence is pushed onto this
+    /// sink's `World`'s trigger queue. Any hook already on `list` for the same event keeps
+    /// running; this one just runs alongside it. Returns the id to pass to
+    /// `World::register_observer` to receive those triggers.
+    pub fn attach<U: Component>(&self, event: Event, list: &mut ComponentList<U>) -> usize
+    {
+        let list_id = list as *const _ as usize;
+        let queue = self.queue.clone();
+        match event
+        {
+            Event::OnAdd => list.chain_on_add(move |entity: &Entity, _| {
+                queue.borrow_mut().push_back((Event::OnAdd, list_id, *entity));
+            }),
+            Event::OnInsert => list.chain_on_insert(move |entity: &Entity, _| {
+                queue.borrow_mut().push_back((Event::OnInsert, list_id, *entity));
+            }),
+            Event::OnRemove => list.chain_on_remove(move |entity: &Entity| {
+                queue.borrow_mut().push_back((Event::OnRemove, list_id, *entity));
+            }),
+        }
+        list_id
+    }
+}
+
+/// The `World` ties entities, components and systems together, and owns the reactive observer
+/// registry used to keep external state in sync with component changes.
+pub struct World<T: ComponentManager, S: SystemManager<T>>
+{
+    pub data: DataHelper<T>,
+    pub systems: S,
+    observers: HashMap<ObserverKey, Vec<Box<Fn(EntityData<T>)>>>,
+    trigger_queue: Rc<RefCell<VecDeque<(Event, usize, Entity)>>>,
+    max_trigger_iterations: usize,
+}
+
+impl<T: ComponentManager, S: SystemManager<T>> World<T, S>
+{
+    pub fn new() -> World<T, S>
+    {
+        World
+        {
+            data: DataHelper::new(),
+            systems: unsafe { SystemManager::new() },
+            observers: HashMap::new(),
+            trigger_queue: Rc::new(RefCell::new(VecDeque::new())),
+            max_trigger_iterations: DEFAULT_MAX_TRIGGER_ITERATIONS,
+        }
+    }
+
+    /// Overrides the default cap on how many times the trigger queue is redrained in a single
+    /// `update` before observer-triggered observers are abandoned for that frame.
+    pub fn set_max_trigger_iterations(&mut self, max: usize)
+    {
+        self.max_trigger_iterations = max;
+    }
+
+    /// A cheap, cloneable handle onto this `World`'s trigger queue, used to wire a
+    /// `ComponentList`'s lifecycle hooks up to `register_observer` via `TriggerSink::attach`.
+    ///
+    /// Split out from `register_observer` because a single method can't take both `&mut self`
+    /// and `&mut world.data.components.some_field` at once — they're both borrows of `world`,
+    /// and the borrow checker won't let them overlap. Getting the sink first, as its own
+    /// statement, lets that borrow end before `attach` borrows the list:
+    ///
+    /// ```ignore
+    /// let sink = world.trigger_sink();
+    /// let pos_id = sink.attach(Event::OnAdd, &mut world.data.components.pos);
+    /// world.register_observer(Event::OnAdd, pos_id, |entity| { ... });
+    /// ```
+    pub fn trigger_sink(&self) -> TriggerSink
+    {
+        TriggerSink { queue: self.trigger_queue.clone() }
+    }
+
+    /// Registers `callback` to fire with the affected `EntityData` whenever `event` happens on
+    /// the list identified by `list_id` (the value `TriggerSink::attach` returned for it).
+    pub fn register_observer<F>(&mut self, event: Event, list_id: usize, callback: F)
+        where F: Fn(EntityData<T>) + 'static
+    {
+        self.observers.entry((event, list_id)).or_insert_with(Vec::new).push(box callback);
+    }
+
+    /// Runs every active system once, then flushes the commands every system queued for the
+    /// frame, then drains the trigger queue into the observers registered via
+    /// `register_observer`. The queue is re-checked until it's empty or `max_trigger_iterations`
+    /// rounds have run, whichever comes first. Observers only receive an `EntityData`, with no
+    /// access to the components, so they cannot themselves make component changes.
+    ///
+    /// Commands are a once-per-frame barrier, not a per-system one: `self.systems.update` runs
+    /// every system to completion before `flush_commands` sees any of their commands, so an
+    /// entity created by one system's `CommandBuffer` isn't visible to a later system in the same
+    /// frame, only from the next frame on.
+    ///
+    /// Returns `false` in the ordinary case where the trigger queue ran dry on its own, or `true`
+    /// if `max_trigger_iterations` was hit first — meaning some observers never got to run, and
+    /// whatever was still queued for them was discarded. A `true` result almost always means a
+    /// set of observers is perpetually re-triggering each other; callers that care can raise
+    /// `set_max_trigger_iterations` or fix the cycle.
+    pub fn update(&mut self) -> bool
+    {
+        unsafe { self.systems.update(&mut self.data); }
+        self.flush_commands();
+        self.flush_triggers()
+    }
+
+    /// Applies every command queued in `self.data.commands` since the last flush, in the order
+    /// it was recorded, then clears the buffer.
+    ///
+    /// This matches over `Command` directly rather than routing through a generic helper with
+    /// one closure per variant: those closures would all need to capture `&mut self.data` at
+    /// once, and the borrow checker won't allow that. Matching in a single loop keeps only one
+    /// borrow of `self.data` alive at a time.
+    fn flush_commands(&mut self)
+    {
+        let commands = self.data.commands.drain();
+        let mut placeholders: HashMap<PlaceholderEntity, Entity> = HashMap::new();
+
+        for command in commands
+        {
+            match command
+            {
+                Command::CreateEntity(placeholder, mut builder) =>
+                {
+                    let entity = self.data.entities.create();
+                    builder.build(BuildData(&entity), &mut self.data.components);
+                    placeholders.insert(placeholder, entity);
+                },
+                Command::RemoveEntity(target) =>
+                {
+                    if let Some(entity) = target.resolve(&placeholders)
+                    {
+                        unsafe { self.data.components.remove_all(&entity); }
+                        self.data.entities.destroy(&entity);
+                    }
+                },
+                Command::ModifyEntity(target, mut modifier) =>
+                {
+                    if let Some(entity) = target.resolve(&placeholders)
+                    {
+                        modifier.modify(ModifyData(&entity), &mut self.data.components);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Returns `true` if `max_trigger_iterations` was hit and the remaining queue was discarded
+    /// unprocessed, `false` if it ran dry on its own.
+    fn flush_triggers(&mut self) -> bool
+    {
+        let mut iterations = 0;
+        while !self.trigger_queue.borrow().is_empty()
+        {
+            if iterations >= self.max_trigger_iterations
+            {
+                self.trigger_queue.borrow_mut().clear();
+                return true;
+            }
+            iterations += 1;
+
+            let pending: Vec<_> = self.trigger_queue.borrow_mut().drain(..).collect();
+            for (event, list_ptr, entity) in pending
+            {
+                if let Some(callbacks) = self.observers.get(&(event, list_ptr))
+                {
+                    for callback in callbacks
+                    {
+                        callback(EntityData(&entity));
+                    }
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Creates a new entity and immediately applies `builder` to populate its components.
+    pub fn create_entity<B: EntityBuilder<T>>(&mut self, mut builder: B) -> Entity
+    {
+        let entity = self.data.entities.create();
+        builder.build(BuildData(&entity), &mut self.data.components);
+        entity
+    }
+
+    /// Applies `modifier` to `entity`'s components, if the entity is still live.
+    pub fn modify_entity<M: EntityModifier<T>>(&mut self, entity: Entity, mut modifier: M)
+    {
+        if self.data.entities.is_valid(&entity)
+        {
+            modifier.modify(ModifyData(&entity), &mut self.data.components);
+        }
+    }
+
+    /// Removes `entity` and every component it had.
+    pub fn remove_entity(&mut self, entity: Entity)
+    {
+        unsafe { self.data.components.remove_all(&entity); }
+        self.data.entities.destroy(&entity);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use component::ComponentList;
+    use Entity;
+
+    struct Components { flag: ComponentList<u32> }
+
+    unsafe impl ComponentManager for Components
+    {
+        unsafe fn new() -> Components { Components { flag: ComponentList::hot() } }
+        unsafe fn remove_all(&mut self, entity: &Entity) { self.flag.remove(entity); }
+    }
+
+    struct NoSystems;
+
+    unsafe impl SystemManager<Components> for NoSystems
+    {
+        unsafe fn new() -> NoSystems { NoSystems }
+        unsafe fn activated(&mut self, _: EntityData<Components>, _: &Components) {}
+        unsafe fn reactivated(&mut self, _: EntityData<Components>, _: &Components) {}
+        unsafe fn deactivated(&mut self, _: EntityData<Components>, _: &Components) {}
+        unsafe fn update(&mut self, _: &mut DataHelper<Components>) {}
+    }
+
+    #[test]
+    fn observer_fires_once_on_add_and_is_flushed_by_update()
+    {
+        let mut world: World<Components, NoSystems> = World::new();
+
+        let sink = world.trigger_sink();
+        let flag_id = sink.attach(Event::OnAdd, &mut world.data.components.flag);
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let seen_in_observer = seen.clone();
+        world.register_observer(Event::OnAdd, flag_id, move |entity: EntityData<Components>| {
+            seen_in_observer.borrow_mut().push(*entity);
+        });
+
+        let entity = world.create_entity(|build: BuildData<Components>, co: &mut Components| {
+            build.insert(&mut co.flag, 42);
+        });
+
+        assert!(seen.borrow().is_empty(), "observer must not fire before the queue is flushed");
+
+        world.update();
+
+        assert_eq!(*seen.borrow(), vec![entity]);
+    }
+
+    #[test]
+    fn update_reports_overflow_when_observers_perpetually_retrigger_each_other()
+    {
+        let mut world: World<Components, NoSystems> = World::new();
+        world.set_max_trigger_iterations(3);
+
+        let sink = world.trigger_sink();
+        let flag_id = sink.attach(Event::OnAdd, &mut world.data.components.flag);
+
+        // Re-enqueues itself on every firing, simulating two observers perpetually re-triggering
+        // each other. Reaches into TriggerSink's queue directly (a private field, but this
+        // submodule can see it) since observers only get an EntityData, with no way to mutate
+        // components and cause a *real* re-trigger themselves.
+        let requeue_entity = Entity(0);
+        let requeue_queue = sink.queue.clone();
+        world.register_observer(Event::OnAdd, flag_id, move |_: EntityData<Components>| {
+            requeue_queue.borrow_mut().push_back((Event::OnAdd, flag_id, requeue_entity));
+        });
+
+        world.create_entity(|build: BuildData<Components>, co: &mut Components| {
+            build.insert(&mut co.flag, 1);
+        });
+
+        assert!(world.update(), "observer queue should have overflowed and been discarded");
+    }
+
+    #[test]
+    fn flush_commands_creates_and_modifies_a_placeholder_in_one_pass()
+    {
+        let mut world: World<Components, NoSystems> = World::new();
+
+        let placeholder = world.data.commands.create_entity(|build: BuildData<Components>, co: &mut Components| {
+            build.insert(&mut co.flag, 1);
+        });
+        world.data.commands.modify_placeholder(placeholder, |modify: ModifyData<Components>, co: &mut Components| {
+            modify.insert(&mut co.flag, 2);
+        });
+
+        world.update();
+
+        let entity = Entity(0);
+        assert_eq!(unsafe { world.data.components.flag.get(&entity) }, Some(2));
+    }
+}